@@ -0,0 +1,6 @@
+#[macro_use]
+pub mod errors;
+pub mod parser;
+pub mod renderer;
+pub mod template;
+pub mod tera;
@@ -0,0 +1,22 @@
+/// A macro definition extracted from a template. Downstream code (macro resolution,
+/// rendering) only ever needs to know a macro's name to index it; the body is rendered
+/// straight from the source template, so it isn't modeled here.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MacroDefinition {
+    pub name: String,
+}
+
+/// The handful of top-level nodes relevant to macro resolution. Everything else in a
+/// template (expressions, loops, plain text, ...) is irrelevant to `Template`/
+/// `MacroCollection` and is dropped during parsing.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    /// `{% extends "file" %}`
+    Extends(String),
+    /// `{% import "file" as namespace %}` / `{% import "file" as namespace export %}`
+    ImportMacro { file: String, namespace: String, exported: bool },
+    /// `{% macro_use "file" %}`
+    MacroUse { file: String },
+    /// `{% macro name(...) %}...{% endmacro %}`
+    MacroDefinition(MacroDefinition),
+}
@@ -0,0 +1,120 @@
+pub mod ast;
+
+use self::ast::{MacroDefinition, Node};
+use errors::Result;
+
+/// Scans a template's `{% ... %}` tags for the ones macro resolution cares about
+/// (`extends`, `import ... as ... [export]`, `macro_use`, `macro ... endmacro`).
+/// Nesting is not tracked: such a tag inside a loop or if body is picked up like a
+/// top-level one. Everything else -- expressions, other tags, plain text -- is irrelevant
+/// to `Template`'s fields and is skipped rather than modeled.
+pub fn parse(name: &str, input: &str) -> Result<Vec<Node>> {
+    let mut nodes = Vec::new();
+    let mut rest = input;
+
+    while let Some(tag_start) = rest.find("{%") {
+        rest = &rest[tag_start + 2..];
+        let tag_end =
+            rest.find("%}").ok_or_else(|| format!("unclosed tag in template `{}`", name))?;
+        let tag = rest[..tag_end].trim();
+        rest = &rest[tag_end + 2..];
+
+        let mut parts = tag.split_whitespace();
+        match parts.next() {
+            Some("extends") => {
+                let file = parse_string(parts.next(), name)?;
+                nodes.push(Node::Extends(file));
+            }
+            Some("import") => {
+                let file = parse_string(parts.next(), name)?;
+                if parts.next() != Some("as") {
+                    bail!("expected `as` in `import` tag in template `{}`", name);
+                }
+                let namespace = parts
+                    .next()
+                    .ok_or_else(|| format!("expected a namespace in `import` tag in template `{}`", name))?
+                    .to_string();
+                let exported = parts.next() == Some("export");
+                nodes.push(Node::ImportMacro { file, namespace, exported });
+            }
+            Some("macro_use") => {
+                let file = parse_string(parts.next(), name)?;
+                nodes.push(Node::MacroUse { file });
+            }
+            Some("macro") => {
+                let macro_name = parts
+                    .next()
+                    .and_then(|s| s.split('(').next())
+                    .filter(|s| !s.is_empty())
+                    .ok_or_else(|| format!("expected a macro name in template `{}`", name))?
+                    .to_string();
+                nodes.push(Node::MacroDefinition(MacroDefinition { name: macro_name }));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(nodes)
+}
+
+fn parse_string(token: Option<&str>, name: &str) -> Result<String> {
+    let token =
+        token.ok_or_else(|| format!("expected a string literal in template `{}`", name))?;
+    let inner = token.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+    match inner {
+        Some(s) => Ok(s.to_string()),
+        None => bail!("expected a quoted string literal in template `{}`", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_import_without_export() {
+        let nodes = parse("t", "{% import \"m.html\" as m %}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![Node::ImportMacro {
+                file: "m.html".to_string(),
+                namespace: "m".to_string(),
+                exported: false,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_import_with_export() {
+        let nodes = parse("t", "{% import \"m.html\" as m export %}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![Node::ImportMacro {
+                file: "m.html".to_string(),
+                namespace: "m".to_string(),
+                exported: true,
+            }]
+        );
+    }
+
+    #[test]
+    fn parses_macro_use_and_extends() {
+        let nodes = parse("t", "{% extends \"base.html\" %}{% macro_use \"m.html\" %}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![
+                Node::Extends("base.html".to_string()),
+                Node::MacroUse { file: "m.html".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_macro_definition_name() {
+        let nodes = parse("t", "{% macro hello(name) %}hi{% endmacro hello %}").unwrap();
+        assert_eq!(
+            nodes,
+            vec![Node::MacroDefinition(MacroDefinition { name: "hello".to_string() })]
+        );
+    }
+}
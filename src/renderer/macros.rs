@@ -1,6 +1,6 @@
 use errors::Result;
 use parser::ast::MacroDefinition;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use template::Template;
 use tera::Tera;
 
@@ -8,20 +8,52 @@ use tera::Tera;
 
 /// Maps { macro => macro_definition }
 pub type MacroDefinitionMap = HashMap<String, MacroDefinition>;
-/// Maps { namespace => ( macro_template, { macro => macro_definition }) }
-pub type MacroNamespaceMap<'a> = HashMap<&'a str, (&'a str, &'a MacroDefinitionMap)>;
+/// Maps { macro => ( macro_template, macro_definition) }, used for macros that are
+/// reachable by their bare name rather than through an explicit namespace
+pub type TextualMacroMap<'a> = HashMap<&'a str, (&'a str, &'a MacroDefinition)>;
 /// Maps { template => { namespace => ( macro_template, { macro => macro_definition }) }
 pub type MacroTemplateMap<'a> = HashMap<&'a str, MacroNamespaceMap<'a>>;
 
-/// Collection of all macro templates by file
+/// All the macros visible from a given template: the ones reachable through an explicit
+/// `namespace::name` as well as the ones imported textually (`macro_use`) and therefore
+/// reachable by bare name.
 #[derive(Clone, Debug, Default)]
+pub struct MacroNamespaceMap<'a> {
+    namespaces: HashMap<&'a str, (&'a str, &'a MacroDefinitionMap)>,
+    /// Flat view of every macro imported without a namespace. If several `macro_use`
+    /// imports define the same name, the last one imported wins.
+    textual: TextualMacroMap<'a>,
+    /// Names of the entries in `namespaces` that were imported with `export` (or inherited
+    /// as such from a parent) and therefore propagate further down the `extends` chain.
+    exported: HashSet<&'a str>,
+}
+
+/// Default maximum depth of the `imported_macro_files`/`parents` recursion in
+/// `add_macros_from_template`, used unless overridden via
+/// `MacroCollection::with_max_import_depth`. Mirrors rust-analyzer's
+/// `EXPANSION_DEPTH_LIMIT`/`FIXED_POINT_LIMIT`: a generous bound that's only ever hit by
+/// a runaway or mutually-recursive set of imports, never by legitimate template trees.
+pub const DEFAULT_MACRO_IMPORT_DEPTH_LIMIT: usize = 128;
+
+/// Collection of all macro templates by file
+#[derive(Clone, Debug)]
 pub struct MacroCollection<'a> {
     macros: MacroTemplateMap<'a>,
+    max_import_depth: usize,
+}
+
+impl<'a> Default for MacroCollection<'a> {
+    fn default() -> Self {
+        MacroCollection {
+            macros: MacroTemplateMap::new(),
+            max_import_depth: DEFAULT_MACRO_IMPORT_DEPTH_LIMIT,
+        }
+    }
 }
 
 impl<'a> MacroCollection<'a> {
     pub fn from_original_template(tpl: &'a Template, tera: &'a Tera) -> MacroCollection<'a> {
-        let mut macro_collection = MacroCollection { macros: MacroTemplateMap::new() };
+        let mut macro_collection = MacroCollection::default();
 
         macro_collection
             .add_macros_from_template(tera, tpl)
@@ -30,6 +62,13 @@ impl<'a> MacroCollection<'a> {
         macro_collection
     }
 
+    /// Overrides the default `imported_macro_files`/`parents` recursion depth
+    /// (`DEFAULT_MACRO_IMPORT_DEPTH_LIMIT`) a `MacroCollection` will accept before bailing
+    /// out with a "macro import depth exceeded" error.
+    pub fn with_max_import_depth(max_import_depth: usize) -> MacroCollection<'a> {
+        MacroCollection { max_import_depth, ..MacroCollection::default() }
+    }
+
     /// Add macros from parsed template to `MacroCollection`
     ///
     /// Macro templates can import other macro templates so the macro loading needs to
@@ -39,35 +78,96 @@ impl<'a> MacroCollection<'a> {
     /// TODO: add checks while building Tera that all the template files with macros are loaded
     /// so we can get rid of Result here
     pub fn add_macros_from_template(
-        self: &mut Self,
+        &mut self,
         tera: &'a Tera,
         template: &'a Template,
+    ) -> Result<()> {
+        let mut visiting = Vec::new();
+        self.add_macros_from_template_checked(tera, template, &mut visiting)
+    }
+
+    /// Same as `add_macros_from_template` but threading a stack of the templates currently
+    /// being visited through the recursion, so that a cycle going through
+    /// `imported_macro_files`/`textual_macro_files`/`parents` is caught before it can
+    /// overflow the stack, instead of relying solely on the `self.macros.contains_key`
+    /// guard (which only protects against direct re-entry once a template is fully loaded).
+    fn add_macros_from_template_checked(
+        &mut self,
+        tera: &'a Tera,
+        template: &'a Template,
+        visiting: &mut Vec<&'a str>,
     ) -> Result<()> {
         let template_name = &template.name[..];
         if self.macros.contains_key(template_name) {
             return Ok(());
         }
 
-        let mut macro_namespace_map = MacroNamespaceMap::new();
+        if let Some(pos) = visiting.iter().position(|&t| t == template_name) {
+            let mut chain: Vec<&str> = visiting[pos..].to_vec();
+            chain.push(template_name);
+            bail!("macro import cycle detected: {}", chain.join(" -> "));
+        }
+
+        if visiting.len() >= self.max_import_depth {
+            bail!(
+                "macro import depth exceeded the limit of {} while importing `{}`",
+                self.max_import_depth,
+                template_name
+            );
+        }
+
+        visiting.push(template_name);
+
+        let mut macro_namespace_map = MacroNamespaceMap::default();
 
         if !template.macros.is_empty() {
-            macro_namespace_map.insert("self", (template_name, &template.macros));
+            macro_namespace_map.namespaces.insert("self", (template_name, &template.macros));
         }
 
-        for &(ref filename, ref namespace) in &template.imported_macro_files {
+        for &(ref filename, ref namespace, exported) in &template.imported_macro_files {
             let macro_tpl = tera.get_template(filename)?;
-            macro_namespace_map.insert(namespace, (filename, &macro_tpl.macros));
-            self.add_macros_from_template(tera, macro_tpl)?;
+            macro_namespace_map.namespaces.insert(namespace, (filename, &macro_tpl.macros));
+            if exported {
+                macro_namespace_map.exported.insert(namespace);
+            }
+            self.add_macros_from_template_checked(tera, macro_tpl, visiting)?;
         }
 
-        self.macros.insert(template_name, macro_namespace_map);
+        // Unqualified (`macro_use`) imports: every macro defined in those files becomes
+        // reachable by its bare name in this template, last import wins on conflicts.
+        for filename in &template.textual_macro_files {
+            let macro_tpl = tera.get_template(filename)?;
+            for (name, definition) in &macro_tpl.macros {
+                macro_namespace_map.textual.insert(name, (filename, definition));
+            }
+            self.add_macros_from_template_checked(tera, macro_tpl, visiting)?;
+        }
 
+        // Glob re-export: a namespace imported with `export` in a parent template stays
+        // visible under the same name in templates that `extend` it, without overwriting
+        // a namespace the child defines or imports itself. The re-exported status carries
+        // over so it keeps propagating further down the `extends` chain.
         for parent in &template.parents {
             let parent = &parent[..];
             let parent_template = tera.get_template(parent)?;
-            self.add_macros_from_template(tera, parent_template)?;
+            self.add_macros_from_template_checked(tera, parent_template, visiting)?;
+
+            if let Some(parent_map) = self.macros.get(parent) {
+                for &namespace in &parent_map.exported {
+                    if let Some(&entry) = parent_map.namespaces.get(namespace) {
+                        if !macro_namespace_map.namespaces.contains_key(namespace) {
+                            macro_namespace_map.namespaces.insert(namespace, entry);
+                            macro_namespace_map.exported.insert(namespace);
+                        }
+                    }
+                }
+            }
         }
 
+        self.macros.insert(template_name, macro_namespace_map);
+
+        visiting.pop();
+
         Ok(())
     }
 
@@ -77,24 +177,183 @@ impl<'a> MacroCollection<'a> {
         macro_namespace: &'a str,
         macro_name: &'a str,
     ) -> Result<(&'a str, &'a MacroDefinition)> {
-        let namespace = self
-            .macros
-            .get(template_name)
-            .and_then(|namespace_map| namespace_map.get(macro_namespace));
+        let namespace_map = if let Some(n) = self.macros.get(template_name) {
+            n
+        } else {
+            bail!(
+            "Macro namespace `{}` was not found in template `{}`. Have you maybe forgotten to import it, or misspelled it?",
+            macro_namespace, template_name
+            )
+        };
 
-        if let Some(n) = namespace {
-            let &(macro_template, macro_definition_map) = n;
+        // An explicit `namespace::name` always takes precedence over a textual match,
+        // as in rust-analyzer's `get_item_or_macro` for real module macros.
+        if !macro_namespace.is_empty() {
+            let namespace = namespace_map.namespaces.get(macro_namespace);
 
-            if let Some(m) = macro_definition_map.get(macro_name).map(|md| (macro_template, md)) {
-                Ok(m)
+            if let Some(n) = namespace {
+                let &(macro_template, macro_definition_map) = n;
+
+                return if let Some(m) =
+                    macro_definition_map.get(macro_name).map(|md| (macro_template, md))
+                {
+                    Ok(m)
+                } else {
+                    bail!(
+                        "Macro `{}::{}` not found in template `{}`",
+                        macro_namespace,
+                        macro_name,
+                        template_name
+                    )
+                };
             } else {
                 bail!(
-                    "Macro `{}::{}` not found in template `{}`",
-                    macro_namespace,
-                    macro_name,
-                    template_name
+                "Macro namespace `{}` was not found in template `{}`. Have you maybe forgotten to import it, or misspelled it?",
+                macro_namespace, template_name
                 )
             }
+        }
+
+        if let Some(&(macro_template, macro_definition)) = namespace_map.textual.get(macro_name) {
+            Ok((macro_template, macro_definition))
+        } else {
+            bail!("Macro `{}` not found in template `{}`", macro_name, template_name)
+        }
+    }
+
+    /// Resolve every macro reachable from any template known to `tera` into a single flat
+    /// index, following the same namespace/textual/exported resolution rules as
+    /// `lookup_macro` but computing them once up front instead of on every call site.
+    pub fn resolve(tera: &'a Tera) -> Result<ResolvedMacros<'a>> {
+        let mut collection = MacroCollection::default();
+
+        for template_name in tera.get_template_names() {
+            let template = tera.get_template(template_name)?;
+            collection.add_macros_from_template(tera, template)?;
+        }
+
+        Ok(collection.flatten())
+    }
+
+    fn flatten(&self) -> ResolvedMacros<'a> {
+        let mut resolved = ResolvedMacros::default();
+
+        for (&template_name, namespace_map) in &self.macros {
+            let template_id = TemplateId(resolved.templates.intern(template_name));
+
+            for (&namespace, &(macro_template, macro_definition_map)) in &namespace_map.namespaces
+            {
+                let namespace_id = NamespaceId(resolved.namespaces.intern(namespace));
+                resolved.known_namespaces.insert((template_id, namespace_id));
+
+                for (name, definition) in macro_definition_map.iter() {
+                    let name_id = NameId(resolved.names.intern(name));
+                    resolved
+                        .index
+                        .insert((template_id, namespace_id, name_id), (macro_template, definition));
+                }
+            }
+
+            // Textual (`macro_use`) entries are indexed under the empty namespace, mirroring
+            // the convention `lookup_macro` uses to tell a bare-name call from a qualified one.
+            let textual_namespace_id = NamespaceId(resolved.namespaces.intern(""));
+            for (&name, &(macro_template, definition)) in &namespace_map.textual {
+                let name_id = NameId(resolved.names.intern(name));
+                resolved
+                    .index
+                    .insert((template_id, textual_namespace_id, name_id), (macro_template, definition));
+            }
+        }
+
+        resolved
+    }
+}
+
+/// Interns `&'a str`s into small `Copy` ids, so `ResolvedMacros`'s index is keyed by ids
+/// rather than by strings. A lookup still hashes each string once to find its id.
+#[derive(Clone, Debug, Default)]
+struct Interner<'a> {
+    ids: HashMap<&'a str, u32>,
+}
+
+impl<'a> Interner<'a> {
+    fn intern(&mut self, s: &'a str) -> u32 {
+        let next_id = self.ids.len() as u32;
+        *self.ids.entry(s).or_insert(next_id)
+    }
+
+    fn get(&self, s: &str) -> Option<u32> {
+        self.ids.get(s).copied()
+    }
+}
+
+/// Id of an interned template name in a `ResolvedMacros` index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct TemplateId(u32);
+/// Id of an interned macro namespace in a `ResolvedMacros` index. The empty namespace is used
+/// for textually-scoped (`macro_use`) macros, same as `lookup_macro`'s `macro_namespace` arg.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct NamespaceId(u32);
+/// Id of an interned macro name in a `ResolvedMacros` index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct NameId(u32);
+
+/// A fully flattened, precomputed view of every `(template, namespace, name)` macro
+/// reachable through `MacroCollection`, including inherited (`extends`/`export`) and
+/// textually-scoped (`macro_use`) entries. Built once via `MacroCollection::resolve` and
+/// held by the renderer. Looking up a macro resolves the three names to their interned ids
+/// (one probe per name) and then makes a single probe into the flat index; it is not a
+/// single hash probe overall.
+#[derive(Clone, Debug, Default)]
+pub struct ResolvedMacros<'a> {
+    templates: Interner<'a>,
+    namespaces: Interner<'a>,
+    names: Interner<'a>,
+    index: HashMap<(TemplateId, NamespaceId, NameId), (&'a str, &'a MacroDefinition)>,
+    /// Every `(template, namespace)` pair imported (or defined as `self`) in that template,
+    /// including namespaces whose file defines no macros, so
+    /// `lookup_macro` can tell "namespace not imported" apart from "name not found in that
+    /// namespace" the same way `MacroCollection::lookup_macro` does.
+    known_namespaces: HashSet<(TemplateId, NamespaceId)>,
+}
+
+impl<'a> ResolvedMacros<'a> {
+    /// Looks up a macro the same way `MacroCollection::lookup_macro` does, but through one
+    /// probe into the precomputed `index` (after one interner probe per name) instead of
+    /// walking the lazy path's nested maps. Error messages intentionally match the lazy path's wording so a
+    /// caller sees the same diagnostic regardless of which lookup it went through.
+    pub fn lookup_macro(
+        &self,
+        template_name: &'a str,
+        macro_namespace: &'a str,
+        macro_name: &'a str,
+    ) -> Result<(&'a str, &'a MacroDefinition)> {
+        let template_id = self.templates.get(template_name).map(TemplateId);
+        let namespace_id = self.namespaces.get(macro_namespace).map(NamespaceId);
+        let name_id = self.names.get(macro_name).map(NameId);
+
+        if let (Some(t), Some(n), Some(m)) = (template_id, namespace_id, name_id) {
+            if let Some(&(macro_template, macro_definition)) = self.index.get(&(t, n, m)) {
+                return Ok((macro_template, macro_definition));
+            }
+        }
+
+        if macro_namespace.is_empty() && template_id.is_some() {
+            bail!("Macro `{}` not found in template `{}`", macro_name, template_name);
+        }
+
+        let namespace_known = match (template_id, namespace_id) {
+            (Some(t), Some(n)) => self.known_namespaces.contains(&(t, n)),
+            _ => false,
+        };
+
+        if namespace_known {
+            bail!(
+                "Macro `{}::{}` not found in template `{}`",
+                macro_namespace,
+                macro_name,
+                template_name
+            )
         } else {
             bail!(
             "Macro namespace `{}` was not found in template `{}`. Have you maybe forgotten to import it, or misspelled it?",
@@ -103,3 +362,132 @@ impl<'a> MacroCollection<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build_tera(templates: &[(&str, &str)]) -> Tera {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(templates.to_vec()).unwrap();
+        tera
+    }
+
+    #[test]
+    fn resolved_macros_matches_lazy_lookup_for_an_imported_macro() {
+        let tera = build_tera(&[
+            ("macros.html", "{% macro hello() %}hi{% endmacro hello %}"),
+            ("child.html", "{% import \"macros.html\" as m %}{{ m::hello() }}"),
+        ]);
+        let tpl = tera.get_template("child.html").unwrap();
+        let lazy = MacroCollection::from_original_template(tpl, &tera);
+        let resolved = MacroCollection::resolve(&tera).unwrap();
+
+        let (lazy_template, _) = lazy.lookup_macro("child.html", "m", "hello").unwrap();
+        let (resolved_template, _) = resolved.lookup_macro("child.html", "m", "hello").unwrap();
+        assert_eq!(lazy_template, "macros.html");
+        assert_eq!(resolved_template, "macros.html");
+    }
+
+    #[test]
+    fn resolved_macros_distinguishes_missing_namespace_from_missing_name() {
+        let tera = build_tera(&[
+            ("macros.html", "{% macro hello() %}hi{% endmacro hello %}"),
+            ("child.html", "{% import \"macros.html\" as m %}{{ m::hello() }}"),
+        ]);
+        let resolved = MacroCollection::resolve(&tera).unwrap();
+
+        let missing_namespace = resolved.lookup_macro("child.html", "missing", "hello").unwrap_err();
+        assert!(missing_namespace.to_string().contains("namespace `missing` was not found"));
+
+        let missing_name = resolved.lookup_macro("child.html", "m", "missing").unwrap_err();
+        assert!(missing_name.to_string().contains("Macro `m::missing` not found"));
+    }
+
+    #[test]
+    fn cycle_detection_names_the_offending_chain() {
+        let tera = build_tera(&[
+            ("a.html", "{% import \"b.html\" as b %}"),
+            ("b.html", "{% import \"a.html\" as a %}"),
+        ]);
+        let tpl = tera.get_template("a.html").unwrap();
+        let mut collection = MacroCollection::default();
+
+        let err = collection.add_macros_from_template(&tera, tpl).unwrap_err();
+        assert!(err.to_string().contains("macro import cycle detected: a.html -> b.html -> a.html"));
+    }
+
+    #[test]
+    fn with_max_import_depth_is_honored() {
+        let tera = build_tera(&[
+            ("a.html", "{% import \"b.html\" as b %}"),
+            ("b.html", ""),
+        ]);
+        let tpl = tera.get_template("a.html").unwrap();
+        let mut collection = MacroCollection::with_max_import_depth(1);
+
+        let err = collection.add_macros_from_template(&tera, tpl).unwrap_err();
+        assert!(err.to_string().contains("macro import depth exceeded the limit of 1"));
+    }
+
+    #[test]
+    fn macro_use_resolves_by_bare_name_and_last_import_wins() {
+        let tera = build_tera(&[
+            ("macros_a.html", "{% macro hello() %}a{% endmacro hello %}"),
+            ("macros_b.html", "{% macro hello() %}b{% endmacro hello %}"),
+            (
+                "child.html",
+                "{% macro_use \"macros_a.html\" %}{% macro_use \"macros_b.html\" %}{{ hello() }}",
+            ),
+        ]);
+        let tpl = tera.get_template("child.html").unwrap();
+        let collection = MacroCollection::from_original_template(tpl, &tera);
+
+        let (macro_template, _) = collection.lookup_macro("child.html", "", "hello").unwrap();
+        assert_eq!(macro_template, "macros_b.html");
+    }
+
+    #[test]
+    fn explicit_namespace_takes_precedence_over_textual_match() {
+        let tera = build_tera(&[
+            ("macros_a.html", "{% macro hello() %}a{% endmacro hello %}"),
+            ("macros_b.html", "{% macro hello() %}b{% endmacro hello %}"),
+            (
+                "child.html",
+                "{% macro_use \"macros_a.html\" %}{% import \"macros_b.html\" as b %}{{ b::hello() }}",
+            ),
+        ]);
+        let tpl = tera.get_template("child.html").unwrap();
+        let collection = MacroCollection::from_original_template(tpl, &tera);
+
+        let (macro_template, _) = collection.lookup_macro("child.html", "b", "hello").unwrap();
+        assert_eq!(macro_template, "macros_b.html");
+    }
+
+    #[test]
+    fn exported_import_propagates_down_the_extends_chain() {
+        let tera = build_tera(&[
+            ("macros.html", "{% macro hello() %}hi{% endmacro hello %}"),
+            ("base.html", "{% import \"macros.html\" as m export %}"),
+            ("child.html", "{% extends \"base.html\" %}"),
+        ]);
+        let tpl = tera.get_template("child.html").unwrap();
+        let collection = MacroCollection::from_original_template(tpl, &tera);
+
+        let (macro_template, _) = collection.lookup_macro("child.html", "m", "hello").unwrap();
+        assert_eq!(macro_template, "macros.html");
+    }
+
+    #[test]
+    fn non_exported_import_stays_private_to_the_importing_template() {
+        let tera = build_tera(&[
+            ("macros.html", "{% macro hello() %}hi{% endmacro hello %}"),
+            ("base.html", "{% import \"macros.html\" as m %}"),
+            ("child.html", "{% extends \"base.html\" %}"),
+        ]);
+        let tpl = tera.get_template("child.html").unwrap();
+        let collection = MacroCollection::from_original_template(tpl, &tera);
+
+        assert!(collection.lookup_macro("child.html", "m", "hello").is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use errors::Result;
+use parser;
+use parser::ast::{MacroDefinition, Node};
+
+/// A parsed template. Only the fields macro resolution needs are modeled here:
+/// rendering proper (expressions, control flow, output) isn't part of this slice of
+/// the crate.
+#[derive(Clone, Debug, Default)]
+pub struct Template {
+    pub name: String,
+    /// Direct `extends` target, if any. Multi-level inheritance chains are walked by
+    /// recursing into each parent's own `parents` in turn, not flattened here.
+    pub parents: Vec<String>,
+    pub macros: HashMap<String, MacroDefinition>,
+    /// `(file, namespace, exported)` for every `{% import "file" as namespace %}`.
+    pub imported_macro_files: Vec<(String, String, bool)>,
+    /// Files brought in via `{% macro_use "file" %}`.
+    pub textual_macro_files: Vec<String>,
+}
+
+impl Template {
+    pub fn new(name: &str, input: &str) -> Result<Template> {
+        let nodes = parser::parse(name, input)?;
+        let mut template = Template { name: name.to_string(), ..Template::default() };
+
+        for node in nodes {
+            match node {
+                Node::Extends(file) => template.parents.push(file),
+                Node::ImportMacro { file, namespace, exported } => {
+                    template.imported_macro_files.push((file, namespace, exported));
+                }
+                Node::MacroUse { file } => template.textual_macro_files.push(file),
+                Node::MacroDefinition(definition) => {
+                    template.macros.insert(definition.name.clone(), definition);
+                }
+            }
+        }
+
+        Ok(template)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_macros_imports_and_parents() {
+        let template = Template::new(
+            "child.html",
+            "{% extends \"base.html\" %}\
+             {% import \"m.html\" as m %}\
+             {% macro_use \"n.html\" %}\
+             {% macro hello() %}hi{% endmacro hello %}",
+        )
+        .unwrap();
+
+        assert_eq!(template.name, "child.html");
+        assert_eq!(template.parents, vec!["base.html".to_string()]);
+        assert_eq!(
+            template.imported_macro_files,
+            vec![("m.html".to_string(), "m".to_string(), false)]
+        );
+        assert_eq!(template.textual_macro_files, vec!["n.html".to_string()]);
+        assert!(template.macros.contains_key("hello"));
+    }
+
+    #[test]
+    fn import_export_flag_is_carried_onto_imported_macro_files() {
+        let template = Template::new("base.html", "{% import \"m.html\" as m export %}").unwrap();
+
+        assert_eq!(
+            template.imported_macro_files,
+            vec![("m.html".to_string(), "m".to_string(), true)]
+        );
+    }
+}
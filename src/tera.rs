@@ -0,0 +1,60 @@
+use std::collections::HashMap;
+
+use errors::{Error, Result};
+use template::Template;
+
+/// Holds every template known to the engine by name. This slice of the crate only
+/// needs enough of `Tera` for `MacroCollection`/`ResolvedMacros` to resolve macros
+/// across templates; rendering proper lives elsewhere.
+#[derive(Clone, Debug, Default)]
+pub struct Tera {
+    templates: HashMap<String, Template>,
+}
+
+impl Tera {
+    pub fn add_raw_template(&mut self, name: &str, content: &str) -> Result<()> {
+        let template = Template::new(name, content)?;
+        self.templates.insert(name.to_string(), template);
+        Ok(())
+    }
+
+    pub fn add_raw_templates<I, N, C>(&mut self, templates: I) -> Result<()>
+    where
+        I: IntoIterator<Item = (N, C)>,
+        N: AsRef<str>,
+        C: AsRef<str>,
+    {
+        for (name, content) in templates {
+            self.add_raw_template(name.as_ref(), content.as_ref())?;
+        }
+        Ok(())
+    }
+
+    pub fn get_template(&self, name: &str) -> Result<&Template> {
+        self.templates
+            .get(name)
+            .ok_or_else(|| Error::from(format!("Template `{}` not found", name)))
+    }
+
+    pub fn get_template_names(&self) -> impl Iterator<Item = &str> {
+        self.templates.keys().map(|s| s.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_raw_templates_registers_every_template() {
+        let mut tera = Tera::default();
+        tera.add_raw_templates(vec![("a.html", "{% macro hi() %}hi{% endmacro hi %}"), ("b.html", "")])
+            .unwrap();
+
+        let mut names: Vec<&str> = tera.get_template_names().collect();
+        names.sort();
+        assert_eq!(names, vec!["a.html", "b.html"]);
+        assert!(tera.get_template("a.html").unwrap().macros.contains_key("hi"));
+        assert!(tera.get_template("missing.html").is_err());
+    }
+}